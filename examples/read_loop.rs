@@ -9,14 +9,12 @@ fn main() {
                 Ok(ref mut k) => {
                     k.open();
                     let mut n = 10;
-                    let mut old = k8055::DigitalChannel::DZERO;
-                    loop {
-                        if n == 0 {
-                            break;
-                        }
-                        let new = k.read_digital_in().unwrap();
-                        if new != old {
-                            old = new;
+                    while n > 0 {
+                        let mut changed = None;
+                        k.watch_digital_in(k8055::DigitalChannel::DALL, |_old, new| {
+                            changed = Some(new);
+                        }).expect("Error reading DI");
+                        if let Some(new) = changed {
                             k.write_digital_out(new).expect("Error writing DO");
                             n -= 1;
                         }
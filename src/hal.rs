@@ -0,0 +1,169 @@
+//! `embedded-hal` compatible wrappers around `K8055` channels.
+//!
+//! Enabled via the `embedded-hal` Cargo feature. Wraps each `DigitalChannel` bit as an
+//! `OutputPin`/`InputPin` and each `AnalogChannel` as a `PwmPin`, so a `K8055` can be driven by
+//! code that's generic over `embedded-hal` instead of this crate's own API.
+//!
+//! `InputPin` lives behind `embedded-hal`'s own `unproven` feature, so a consumer enabling this
+//! crate's `embedded-hal` feature also needs to enable `unproven` on its own `embedded-hal`
+//! dependency, or `DigitalPin`'s `InputPin` impl won't resolve.
+
+use std::cell::RefCell;
+
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::PwmPin;
+
+use {AnalogChannel, DigitalChannel, K8055, SimTransport};
+
+/// A single digital channel of a `K8055`, borrowed as an `embedded-hal` pin.
+///
+/// Reading a pin requires a USB transfer, which `embedded-hal`'s `InputPin::is_high` doesn't
+/// allow for (it takes `&self`); the `K8055` is therefore borrowed through a `RefCell` so the
+/// read can still happen behind a shared reference.
+pub struct DigitalPin<'a, 'b: 'a> {
+    k8055: RefCell<&'a mut K8055<'b>>,
+    bit: DigitalChannel,
+}
+
+impl<'a, 'b> DigitalPin<'a, 'b> {
+    /// Wrap digital channel `bit` of `k8055` as an `embedded-hal` pin.
+    pub fn new(k8055: &'a mut K8055<'b>, bit: DigitalChannel) -> DigitalPin<'a, 'b> {
+        DigitalPin {
+            k8055: RefCell::new(k8055),
+            bit: bit,
+        }
+    }
+}
+
+impl<'a, 'b> OutputPin for DigitalPin<'a, 'b> {
+    fn set_low(&mut self) {
+        let mut k = self.k8055.borrow_mut();
+        let mask = k.get_digital_out() & !self.bit;
+        let _ = k.write_digital_out(mask);
+    }
+
+    fn set_high(&mut self) {
+        let mut k = self.k8055.borrow_mut();
+        let mask = k.get_digital_out() | self.bit;
+        let _ = k.write_digital_out(mask);
+    }
+}
+
+impl<'a, 'b> InputPin for DigitalPin<'a, 'b> {
+    fn is_high(&self) -> bool {
+        self.k8055
+            .borrow_mut()
+            .read_digital_in_mask(self.bit)
+            .map(|v| v == self.bit)
+            .unwrap_or(false)
+    }
+
+    fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+/// Which `K8055` analog output a `PwmChannel` drives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnalogOut {
+    /// Analog output 1.
+    A1,
+    /// Analog output 2.
+    A2,
+}
+
+/// One analog output of a `K8055`, borrowed as an `embedded-hal` `PwmPin` with an 8-bit duty
+/// cycle (`0..=255`).
+///
+/// The card drives its analog outputs continuously once set, so `enable`/`disable` are no-ops:
+/// there's no separate PWM-enable line to toggle.
+pub struct PwmChannel<'a, 'b: 'a> {
+    k8055: RefCell<&'a mut K8055<'b>>,
+    out: AnalogOut,
+}
+
+impl<'a, 'b> PwmChannel<'a, 'b> {
+    /// Wrap analog output `out` of `k8055` as an `embedded-hal` `PwmPin`.
+    pub fn new(k8055: &'a mut K8055<'b>, out: AnalogOut) -> PwmChannel<'a, 'b> {
+        PwmChannel {
+            k8055: RefCell::new(k8055),
+            out: out,
+        }
+    }
+}
+
+impl<'a, 'b> PwmPin for PwmChannel<'a, 'b> {
+    type Duty = u8;
+
+    fn disable(&mut self) {}
+
+    fn enable(&mut self) {}
+
+    fn get_duty(&self) -> u8 {
+        let mut k = self.k8055.borrow_mut();
+        match self.out {
+            AnalogOut::A1 => match k.get_analog_out1() {
+                AnalogChannel::A1(v) => v,
+                _ => 0,
+            },
+            AnalogOut::A2 => match k.get_analog_out2() {
+                AnalogChannel::A2(v) => v,
+                _ => 0,
+            },
+        }
+    }
+
+    fn get_max_duty(&self) -> u8 {
+        255
+    }
+
+    fn set_duty(&mut self, duty: u8) {
+        let mut k = self.k8055.borrow_mut();
+        let p = match self.out {
+            AnalogOut::A1 => AnalogChannel::A1(duty),
+            AnalogOut::A2 => AnalogChannel::A2(duty),
+        };
+        let _ = k.write_analog_out(p);
+    }
+}
+
+#[test()]
+fn digital_pin_set_high_and_low_update_digital_out() {
+    let mut k = K8055::from_transport(SimTransport::new());
+    assert!(k.open());
+    {
+        let mut pin = DigitalPin::new(&mut k, DigitalChannel::D3);
+        pin.set_high();
+    }
+    assert_eq!(k.get_digital_out(), DigitalChannel::D3);
+
+    {
+        let mut pin = DigitalPin::new(&mut k, DigitalChannel::D3);
+        pin.set_low();
+    }
+    assert_eq!(k.get_digital_out(), DigitalChannel::DZERO);
+}
+
+#[test()]
+fn digital_pin_is_high_reads_injected_input() {
+    let mut sim = SimTransport::new();
+    sim.set_digital_in(DigitalChannel::D3);
+    let mut k = K8055::from_transport(sim);
+    assert!(k.open());
+
+    let pin = DigitalPin::new(&mut k, DigitalChannel::D3);
+    assert!(pin.is_high());
+    assert!(!pin.is_low());
+}
+
+#[test()]
+fn pwm_channel_get_and_set_duty() {
+    let mut k = K8055::from_transport(SimTransport::new());
+    assert!(k.open());
+    let mut pwm = PwmChannel::new(&mut k, AnalogOut::A1);
+
+    assert_eq!(pwm.get_duty(), 0);
+    pwm.set_duty(123);
+    assert_eq!(pwm.get_duty(), 123);
+    assert_eq!(pwm.get_max_duty(), 255);
+}
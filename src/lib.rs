@@ -19,17 +19,41 @@
 //!
 //! See the Vellemann [Homepage](http://www.velleman.eu/products/view/?id=351346) for the
 //! hardware specification.
+//!
+//! ## Known limitations
+//!
+//! `K8055`'s device handle is tied to the lifetime of the `Context` it was opened from and isn't
+//! `'static`, and the `libusb` dependency this crate builds on only exposes synchronous
+//! interrupt transfers. Neither leaves room for a real background thread or libusb's
+//! asynchronous transfer submission API, so two things that would ordinarily be async are not:
+//!
+//! - `try_write`/`try_read`/`poll_inputs` are still blocking calls; they just use a much shorter
+//!   transfer timeout than `read_digital_in`/`write_digital_out` so a non-responding card fails
+//!   fast instead of stalling the caller for a full second.
+//! - `watch`/`poll_events` do not spawn a poller of their own. Call them repeatedly from your own
+//!   event loop; they only do the edge/threshold diffing for you.
 
 #![crate_type = "lib"]
 
 #[macro_use]
 extern crate bitflags;
+#[cfg(feature = "embedded-hal")]
+extern crate embedded_hal;
 #[macro_use]
 extern crate error_chain;
 extern crate libusb;
-extern crate rustc_serialize;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+#[cfg(feature = "embedded-hal")]
+pub mod hal;
 
 use std::default::Default;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
 use std::time::Duration;
 use libusb::{Context, Device, DeviceHandle};
 
@@ -99,31 +123,299 @@ See the jumper setting on your card for the correct address.
 
 const VENDOR_ID: u16 = 0x10cf;
 
-#[derive(Debug)]
-enum Packet {
+/// The 8-byte command/status packets a `Transport` exchanges with a card.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Packet {
+    /// Set the digital outputs and both analog outputs in one go.
     SetAnalogDigital(u8, u8, u8),
-    Status(u8, u8, u8, u8),
+    /// Reset the hardware pulse counter for digital input 1 or 2.
+    ResetCounter(u8),
+    /// Set the debounce time (ms) for digital input 1 or 2's pulse counter.
+    SetDebounce(u8, u8),
+    /// `dig, status, ana1, ana2, counter1, counter2`.
+    Status(u8, u8, u8, u8, u16, u16),
 }
 
-#[derive(Default)]
+#[derive(Default, Serialize, Deserialize)]
 struct State {
     dig: u8,
     ana1: u8,
     ana2: u8,
+    debounce1: u8,
+    debounce2: u8,
+}
+
+/// An on-disk profile applied by `K8055::from_config` when opening a card.
+///
+/// Stored as JSON via `serde_json`, so it can be hand-edited or generated alongside the rest of
+/// a deployment's configuration.
+#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+pub struct Config {
+    /// Which card to bind to, e.g. `CardAddress::CARD_1.bits`, or `CardAddress::CARD_ANY.bits`
+    /// to use the first card found.
+    pub card_address: u16,
+    /// Digital outputs to apply right after `open()`.
+    pub initial_digital_out: u8,
+    /// Analog output 1 to apply right after `open()`.
+    pub initial_analog_out1: u8,
+    /// Analog output 2 to apply right after `open()`.
+    pub initial_analog_out2: u8,
+    /// Debounce time (ms) to apply to counter 1 right after `open()`.
+    pub debounce1: u8,
+    /// Debounce time (ms) to apply to counter 2 right after `open()`.
+    pub debounce2: u8,
+    /// Human readable names for the digital channels, indexed `0..8` for `D1..D8`.
+    pub digital_names: Vec<String>,
+    /// Human readable names for the analog channels, indexed `0..2` for `A1, A2`.
+    pub analog_names: Vec<String>,
+}
+
+impl Config {
+    /// Load a `Config` from the JSON file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let mut f = try!(File::open(path).chain_err(|| "Unable to open config file"));
+        let mut s = String::new();
+        try!(f.read_to_string(&mut s).chain_err(|| "Unable to read config file"));
+        serde_json::from_str(&s).chain_err(|| "Unable to decode config file")
+    }
+}
+
+/// The interrupt transfer timeout used before `K8055::set_timeout` has been called.
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+
+/// A digital edge or analog threshold crossing reported by `K8055::poll_events`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// The masked digital input bits went from `off` to `on`.
+    DigitalRising(DigitalChannel),
+    /// The masked digital input bits went from `on` to `off`.
+    DigitalFalling(DigitalChannel),
+    /// Analog input 1 crossed its configured threshold and is now above it.
+    Analog1Above(u8),
+    /// Analog input 1 crossed its configured threshold and is now below it.
+    Analog1Below(u8),
+    /// Analog input 2 crossed its configured threshold and is now above it.
+    Analog2Above(u8),
+    /// Analog input 2 crossed its configured threshold and is now below it.
+    Analog2Below(u8),
+}
+
+/// A threshold watch on one analog input, with a hysteresis band to suppress noise.
+#[derive(Debug, Clone, Copy)]
+struct AnalogWatch {
+    threshold: u8,
+    hysteresis: u8,
+    above: Option<bool>,
+}
+
+impl AnalogWatch {
+    fn new(threshold: u8, hysteresis: u8) -> AnalogWatch {
+        AnalogWatch {
+            threshold: threshold,
+            hysteresis: hysteresis,
+            above: None,
+        }
+    }
+
+    /// Feed a new reading in, returning `Some(true)`/`Some(false)` if it just crossed out of
+    /// the hysteresis band on the high/low side respectively, or `None` if it's unchanged or
+    /// still inside the band.
+    fn update(&mut self, value: u8) -> Option<bool> {
+        let high = self.threshold.saturating_add(self.hysteresis);
+        let low = self.threshold.saturating_sub(self.hysteresis);
+        let side = if value >= high {
+            Some(true)
+        } else if value <= low {
+            Some(false)
+        } else {
+            self.above
+        };
+        if side.is_some() && side != self.above {
+            self.above = side;
+            side
+        } else {
+            self.above = side;
+            None
+        }
+    }
+}
+
+/// Abstracts how a `K8055` exchanges packets with a card, so the same logic can run against
+/// either a real USB device or an in-memory simulator such as `SimTransport`.
+pub trait Transport {
+    /// Send `p` to the card. Returns `false` on failure.
+    fn write_packet(&mut self, p: &Packet) -> bool;
+    /// Read the card's current status. Returns `None` on failure.
+    fn read_packet(&mut self) -> Option<Packet>;
+    /// Change the timeout applied to subsequent transfers, if this transport has one.
+    ///
+    /// Transports with no notion of a timeout (e.g. `SimTransport`) can ignore this.
+    fn set_timeout(&mut self, timeout: Duration) {
+        let _ = timeout;
+    }
+}
+
+/// The default `Transport`, backed by a real USB interrupt endpoint.
+struct UsbTransport<'a> {
+    hd: DeviceHandle<'a>,
+    timeout: Duration,
+}
+
+impl<'a> Transport for UsbTransport<'a> {
+    fn write_packet(&mut self, p: &Packet) -> bool {
+        let _ = detach_and_claim(&mut self.hd);
+        match encode(p) {
+            Ok(data) => self.hd.write_interrupt(0x1, &data, self.timeout).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn read_packet(&mut self) -> Option<Packet> {
+        let _ = detach_and_claim(&mut self.hd);
+        let mut data = [0u8; 8];
+        if self.hd.read_interrupt(0x81, &mut data, self.timeout).is_ok() {
+            decode(&data).ok()
+        } else {
+            None
+        }
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+}
+
+fn encode(p: &Packet) -> Result<[u8; 8]> {
+    match *p {
+        Packet::SetAnalogDigital(dig, ana1, ana2) => {
+            Ok([5u8, dig, ana1, ana2, 0u8, 0u8, 0u8, 0u8])
+        }
+        Packet::ResetCounter(1) => Ok([3u8, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8]),
+        Packet::ResetCounter(2) => Ok([4u8, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8]),
+        Packet::SetDebounce(1, time) => Ok([1u8, time, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8]),
+        Packet::SetDebounce(2, time) => Ok([2u8, time, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8]),
+        _ => Err(libusb::Error::InvalidParam.into()),
+    }
+}
+
+fn decode(d: &[u8]) -> Result<Packet> {
+    let counter1 = d[4] as u16 | (d[5] as u16) << 8;
+    let counter2 = d[6] as u16 | (d[7] as u16) << 8;
+    Ok(Packet::Status(d[0], d[1], d[2], d[3], counter1, counter2))
+}
+
+fn detach_and_claim(hd: &mut DeviceHandle) -> Result<()> {
+    try!(hd.kernel_driver_active(0));
+    try!(hd.detach_kernel_driver(0));
+    try!(hd.claim_interface(0));
+    Ok(())
+}
+
+/// An in-memory software model of a K8055 card.
+///
+/// Keeps its own output state, echoes `SetAnalogDigital` back into its `Status` replies, and
+/// lets a test inject digital/analog input values and assert on the packets a `K8055` sent it.
+/// This makes the whole library testable without a card plugged in.
+#[derive(Default)]
+pub struct SimTransport {
+    state: State,
+    digital_in: DigitalChannel,
+    analog_in1: u8,
+    analog_in2: u8,
+    counter1: u16,
+    counter2: u16,
+    sent: Vec<Packet>,
+}
+
+impl SimTransport {
+    /// A simulated card with every input and output at zero.
+    pub fn new() -> SimTransport {
+        Default::default()
+    }
+
+    /// Set the digital input value `read_packet` reports, until a `SetAnalogDigital` write
+    /// echoes over it.
+    pub fn set_digital_in(&mut self, d: DigitalChannel) {
+        self.digital_in = d;
+    }
+
+    /// Set the analog input 1 value `read_packet` reports, until a `SetAnalogDigital` write
+    /// echoes over it.
+    pub fn set_analog_in1(&mut self, v: u8) {
+        self.analog_in1 = v;
+    }
+
+    /// Set the analog input 2 value `read_packet` reports, until a `SetAnalogDigital` write
+    /// echoes over it.
+    pub fn set_analog_in2(&mut self, v: u8) {
+        self.analog_in2 = v;
+    }
+
+    /// Every packet written to this transport so far, in order.
+    pub fn sent_packets(&self) -> &[Packet] {
+        &self.sent
+    }
+}
+
+impl Transport for SimTransport {
+    fn write_packet(&mut self, p: &Packet) -> bool {
+        match *p {
+            Packet::SetAnalogDigital(d, a1, a2) => {
+                self.state.dig = d;
+                self.state.ana1 = a1;
+                self.state.ana2 = a2;
+                // echo the outputs back as the inputs the following status replies report
+                self.digital_in = DigitalChannel::from_bits_truncate(d);
+                self.analog_in1 = a1;
+                self.analog_in2 = a2;
+            }
+            Packet::SetDebounce(1, time) => self.state.debounce1 = time,
+            Packet::SetDebounce(_, time) => self.state.debounce2 = time,
+            Packet::ResetCounter(1) => self.counter1 = 0,
+            Packet::ResetCounter(_) => self.counter2 = 0,
+            Packet::Status(..) => return false,
+        }
+        self.sent.push(*p);
+        true
+    }
+
+    fn read_packet(&mut self) -> Option<Packet> {
+        Some(Packet::Status(
+            self.digital_in.bits,
+            0,
+            self.analog_in1,
+            self.analog_in2,
+            self.counter1,
+            self.counter2,
+        ))
+    }
+}
+
+impl Default for DigitalChannel {
+    fn default() -> DigitalChannel {
+        DigitalChannel::DZERO
+    }
 }
 
 /// Object controlling one Vellemann K8055 card.
 pub struct K8055<'a> {
     dev: Option<Device<'a>>,
-    hd: Option<DeviceHandle<'a>>,
+    transport: Option<Box<Transport + 'a>>,
     state: State,
+    timeout: Duration,
+    watch_mask: DigitalChannel,
+    last_digital_in: Option<DigitalChannel>,
+    analog1_watch: Option<AnalogWatch>,
+    analog2_watch: Option<AnalogWatch>,
+    digital_names: Vec<String>,
+    analog_names: Vec<String>,
 }
 
 impl<'a> K8055<'a> {
     /// Create a new K8055 instance with the first card found on the system.
     ///
     /// May return `None` if no card was found connected to the system.
-    pub fn new(ctx: &mut Context) -> Result<K8055> {
+    pub fn new(ctx: &Context) -> Result<K8055> {
         K8055::new_addr(ctx, CardAddress::CARD_ANY)
     }
 
@@ -131,7 +423,7 @@ impl<'a> K8055<'a> {
     ///
     /// See the hardware jumpers on the card for your card's address. May return `None` if no card
     /// with the address `addr` can be found connected to the system.
-    pub fn new_addr(ctx: &mut Context, addr: CardAddress) -> Result<K8055> {
+    pub fn new_addr(ctx: &Context, addr: CardAddress) -> Result<K8055> {
         let mut d = None;
         {
             for dev in ctx.devices().unwrap().iter() {
@@ -158,8 +450,15 @@ impl<'a> K8055<'a> {
         if d.is_some() {
             let k8055 = K8055 {
                 dev: d,
-                hd: None,
+                transport: None,
                 state: Default::default(),
+                timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+                watch_mask: DigitalChannel::DALL,
+                last_digital_in: None,
+                analog1_watch: None,
+                analog2_watch: None,
+                digital_names: Vec::new(),
+                analog_names: Vec::new(),
             };
             Ok(k8055)
         } else {
@@ -167,29 +466,243 @@ impl<'a> K8055<'a> {
         }
     }
 
+    /// Build and open a `K8055` using the profile loaded from the JSON file at `path`.
+    ///
+    /// Selects `config.card_address`, opens the card, then pushes the configured initial
+    /// output state and debounce times in one call.
+    pub fn from_config<P: AsRef<Path>>(ctx: &Context, path: P) -> Result<K8055> {
+        let config = try!(Config::load(path));
+        let addr = CardAddress::from_bits(config.card_address).unwrap_or(CardAddress::CARD_ANY);
+        let mut k = try!(K8055::new_addr(ctx, addr));
+        if !k.open() {
+            return Err(libusb::Error::NoDevice.into());
+        }
+        try!(k.write(&Packet::SetAnalogDigital(
+            config.initial_digital_out,
+            config.initial_analog_out1,
+            config.initial_analog_out2,
+        )));
+        try!(k.set_debounce1(config.debounce1));
+        try!(k.set_debounce2(config.debounce2));
+        k.digital_names = config.digital_names;
+        k.analog_names = config.analog_names;
+        Ok(k)
+    }
+
     /// Open the device for starting IO operations.
     ///
     /// Returns `true` if the device was successfully opened or is already open. Returns `false`
     /// if the device can't be opened.
     pub fn open(&mut self) -> bool {
         // device already open
-        if self.hd.is_some() {
+        if self.transport.is_some() {
             return true;
         }
+        let timeout = self.timeout;
         match self.dev {
-            Some(ref mut d) => {
-                self.hd = d.open().ok();
-                true
-            }
+            Some(ref mut d) => match d.open() {
+                Ok(hd) => {
+                    self.transport = Some(Box::new(UsbTransport {
+                        hd: hd,
+                        timeout: timeout,
+                    }));
+                    true
+                }
+                Err(_) => false,
+            },
             None => false,
         }
     }
 
+    /// Build a `K8055` around an already-open `Transport`, bypassing USB discovery entirely.
+    ///
+    /// This is how tests exercise the library against a `SimTransport` instead of real
+    /// hardware.
+    pub fn from_transport<T: Transport + 'a>(transport: T) -> K8055<'a> {
+        K8055 {
+            dev: None,
+            transport: Some(Box::new(transport)),
+            state: Default::default(),
+            timeout: Duration::from_millis(DEFAULT_TIMEOUT_MS),
+            watch_mask: DigitalChannel::DALL,
+            last_digital_in: None,
+            analog1_watch: None,
+            analog2_watch: None,
+            digital_names: Vec::new(),
+            analog_names: Vec::new(),
+        }
+    }
+
     /// Set all analog and digital values to zero.
     pub fn reset(&mut self) -> Result<()> {
         self.write(&Packet::SetAnalogDigital(0u8, 0u8, 0u8))
     }
 
+    /// Set the timeout used for the interrupt transfers backing every read/write.
+    ///
+    /// Defaults to 1000ms. A shorter timeout makes a blocking `read`/`write` return sooner at
+    /// the cost of spurious `Err` results if the card is slow to answer. Applies immediately to
+    /// an already-open transport, not just to ones opened afterwards.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+        if let Some(ref mut t) = self.transport {
+            t.set_timeout(timeout);
+        }
+    }
+
+    /// The timeout currently used for the interrupt transfers backing every read/write.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    // non-blocking
+    //
+    // The configurable timeout above is the deliverable of this section; see the crate-level
+    // "Known limitations" section for why try_write/try_read/poll_inputs below fall short of a
+    // real non-blocking surface.
+
+    /// Attempt to send `p`, giving up quickly instead of blocking for `self.timeout`.
+    ///
+    /// Still a synchronous call, not an async one -- see the crate-level "Known limitations"
+    /// section for why. A slow-but-working card can still make this return `false` without the
+    /// write ever reaching it, so prefer `write_digital_out`/`write_analog_out` when delivery
+    /// matters more than latency.
+    pub fn try_write(&mut self, p: Packet) -> bool {
+        let saved = self.timeout;
+        self.set_timeout(Duration::from_millis(1));
+        let ok = self.write(&p).is_ok();
+        self.set_timeout(saved);
+        ok
+    }
+
+    /// Attempt a status read, giving up quickly instead of blocking for `self.timeout`.
+    ///
+    /// Returns `None` if the card didn't answer within the short internal timeout, rather than
+    /// blocking for `self.timeout` like `read_digital_in` and friends do. See `try_write` for
+    /// why this is a fast-failing synchronous call rather than a true asynchronous libusb
+    /// transfer.
+    pub fn try_read(&mut self) -> Option<Packet> {
+        let saved = self.timeout;
+        self.set_timeout(Duration::from_millis(1));
+        let r = self.read().ok();
+        self.set_timeout(saved);
+        r
+    }
+
+    /// Poll for a fresh status report without blocking for long.
+    ///
+    /// Call this repeatedly from your own event loop in place of a busy `read_digital_in()`
+    /// loop; it only returns `Some` once the card has actually answered.
+    pub fn poll_inputs(&mut self) -> Option<Packet> {
+        self.try_read()
+    }
+
+    // watching
+
+    /// Restrict `poll_events` to only report digital edges on the bits set in `mask`.
+    ///
+    /// Defaults to `DALL`, i.e. every digital input is watched.
+    pub fn set_watch_mask(&mut self, mask: DigitalChannel) {
+        self.watch_mask = mask;
+    }
+
+    /// Watch analog input 1 for crossings of `threshold`, ignoring readings within
+    /// `hysteresis` of it so a noisy ADC reading near the edge doesn't produce repeated events.
+    pub fn watch_analog1(&mut self, threshold: u8, hysteresis: u8) {
+        self.analog1_watch = Some(AnalogWatch::new(threshold, hysteresis));
+    }
+
+    /// Watch analog input 2 for crossings of `threshold`. See `watch_analog1`.
+    pub fn watch_analog2(&mut self, threshold: u8, hysteresis: u8) {
+        self.analog2_watch = Some(AnalogWatch::new(threshold, hysteresis));
+    }
+
+    /// Stop watching both analog inputs for threshold crossings.
+    pub fn clear_analog_watches(&mut self) {
+        self.analog1_watch = None;
+        self.analog2_watch = None;
+    }
+
+    /// Take one status reading and report every digital edge and analog threshold crossing
+    /// since the previous call.
+    ///
+    /// Does not spawn a poller of its own -- see the crate-level "Known limitations" section.
+    /// Call this repeatedly from your own event loop, much like an `epoll_wait` call returning
+    /// the events that became ready since it was last polled. The first call after construction
+    /// only establishes the digital baseline and reports no digital edges; `set_watch_mask`
+    /// keeps that baseline. A newly set analog watch has no baseline, so its first reading
+    /// outside the hysteresis band is reported as an above/below event.
+    pub fn poll_events(&mut self) -> Result<Vec<Event>> {
+        let (dig, a1, a2) = match try!(self.read()) {
+            Packet::Status(dig, _, a1, a2, _, _) => (dig, a1, a2),
+            _ => return Err(libusb::Error::InvalidParam.into()),
+        };
+        let mut events = Vec::new();
+        let raw = DigitalChannel::from_bits(dig).unwrap();
+        let new = raw & self.watch_mask;
+        if let Some(old) = self.last_digital_in {
+            let old = old & self.watch_mask;
+            let risen = new & !old;
+            let fallen = old & !new;
+            if risen != DigitalChannel::DZERO {
+                events.push(Event::DigitalRising(risen));
+            }
+            if fallen != DigitalChannel::DZERO {
+                events.push(Event::DigitalFalling(fallen));
+            }
+        }
+        self.last_digital_in = Some(raw);
+
+        if let Some(ref mut w) = self.analog1_watch {
+            match w.update(a1) {
+                Some(true) => events.push(Event::Analog1Above(a1)),
+                Some(false) => events.push(Event::Analog1Below(a1)),
+                None => (),
+            }
+        }
+        if let Some(ref mut w) = self.analog2_watch {
+            match w.update(a2) {
+                Some(true) => events.push(Event::Analog2Above(a2)),
+                Some(false) => events.push(Event::Analog2Below(a2)),
+                None => (),
+            }
+        }
+        Ok(events)
+    }
+
+    /// Like `poll_events`, but invoke `callback` for every event instead of collecting them.
+    ///
+    /// Same caveat as `poll_events`: this takes one reading and returns, it does not spawn a
+    /// background poller -- you still drive the polling loop yourself.
+    pub fn watch<F: FnMut(Event)>(&mut self, mut callback: F) -> Result<()> {
+        for e in try!(self.poll_events()) {
+            callback(e);
+        }
+        Ok(())
+    }
+
+    /// Take one digital input reading masked with `mask` and invoke `callback(old, new)`
+    /// whenever it differs from the previous call's reading.
+    ///
+    /// This shares its baseline reading with `poll_events`/`watch`, so replaces the
+    /// hand-rolled "read, compare with the last reading" loop every caller would otherwise
+    /// have to write for edge detection.
+    pub fn watch_digital_in<F>(&mut self, mask: DigitalChannel, mut callback: F) -> Result<()>
+    where
+        F: FnMut(DigitalChannel, DigitalChannel),
+    {
+        let raw = try!(self.read_digital_in());
+        let new = raw & mask;
+        if let Some(old) = self.last_digital_in {
+            let old = old & mask;
+            if old != new {
+                callback(old, new);
+            }
+        }
+        self.last_digital_in = Some(raw);
+        Ok(())
+    }
+
     // digital
 
     /// Write the digital value `d` to the outports.
@@ -227,7 +740,7 @@ impl<'a> K8055<'a> {
     /// Returns `None` on failure
     pub fn read_digital_in(&mut self) -> Result<DigitalChannel> {
         match self.read() {
-            Ok(Packet::Status(dig, _, _, _)) => Ok(DigitalChannel::from_bits(dig).unwrap()),
+            Ok(Packet::Status(dig, _, _, _, _, _)) => Ok(DigitalChannel::from_bits(dig).unwrap()),
             Err(e) => Err(e),
             _ => Err(libusb::Error::InvalidParam.into()),
         }
@@ -270,7 +783,7 @@ impl<'a> K8055<'a> {
     /// Returns `None` on failure.
     pub fn read_analog_in1(&mut self) -> Result<AnalogChannel> {
         match self.read() {
-            Ok(Packet::Status(_, _, a1, _)) => Ok(AnalogChannel::A1(a1)),
+            Ok(Packet::Status(_, _, a1, _, _, _)) => Ok(AnalogChannel::A1(a1)),
             Err(e) => Err(e),
             _ => Err(libusb::Error::InvalidParam.into()),
         }
@@ -281,100 +794,286 @@ impl<'a> K8055<'a> {
     /// Returns `None` on failure.
     pub fn read_analog_in2(&mut self) -> Result<AnalogChannel> {
         match self.read() {
-            Ok(Packet::Status(_, _, _, a2)) => Ok(AnalogChannel::A2(a2)),
+            Ok(Packet::Status(_, _, _, a2, _, _)) => Ok(AnalogChannel::A2(a2)),
             Err(e) => Err(e),
             _ => Err(libusb::Error::InvalidParam.into()),
         }
     }
 
+    // counters
+
+    /// Read the hardware pulse counter for digital input 1.
+    ///
+    /// The counter increments on every falling edge of D1 and is debounced by `set_debounce1`.
+    pub fn read_counter1(&mut self) -> Result<u16> {
+        match self.read() {
+            Ok(Packet::Status(_, _, _, _, c1, _)) => Ok(c1),
+            Err(e) => Err(e),
+            _ => Err(libusb::Error::InvalidParam.into()),
+        }
+    }
+
+    /// Read the hardware pulse counter for digital input 2.
+    ///
+    /// The counter increments on every falling edge of D2 and is debounced by `set_debounce2`.
+    pub fn read_counter2(&mut self) -> Result<u16> {
+        match self.read() {
+            Ok(Packet::Status(_, _, _, _, _, c2)) => Ok(c2),
+            Err(e) => Err(e),
+            _ => Err(libusb::Error::InvalidParam.into()),
+        }
+    }
+
+    /// Reset the pulse counter for digital input 1 back to zero.
+    pub fn reset_counter1(&mut self) -> Result<()> {
+        self.write(&Packet::ResetCounter(1))
+    }
+
+    /// Reset the pulse counter for digital input 2 back to zero.
+    pub fn reset_counter2(&mut self) -> Result<()> {
+        self.write(&Packet::ResetCounter(2))
+    }
+
+    /// Set the debounce time (in ms) the card applies to digital input 1 before the pulse
+    /// counter sees a falling edge.
+    pub fn set_debounce1(&mut self, ms: u8) -> Result<()> {
+        self.write(&Packet::SetDebounce(1, ms))
+    }
+
+    /// Set the debounce time (in ms) the card applies to digital input 2 before the pulse
+    /// counter sees a falling edge.
+    pub fn set_debounce2(&mut self, ms: u8) -> Result<()> {
+        self.write(&Packet::SetDebounce(2, ms))
+    }
+
+    /// The debounce time last set for digital input 1, in ms.
+    pub fn debounce1(&self) -> u8 {
+        self.state.debounce1
+    }
+
+    /// The debounce time last set for digital input 2, in ms.
+    pub fn debounce2(&self) -> u8 {
+        self.state.debounce2
+    }
+
+    // naming
+
+    /// The human-readable name given to digital channel `D1..D8` (index `0..8`) by
+    /// `Config::digital_names`, if `from_config` was used to build this `K8055` and the profile
+    /// named that channel.
+    pub fn digital_channel_name(&self, index: usize) -> Option<&str> {
+        self.digital_names.get(index).map(String::as_str)
+    }
+
+    /// The human-readable name given to analog channel `A1`/`A2` (index `0..2`) by
+    /// `Config::analog_names`, if `from_config` was used to build this `K8055` and the profile
+    /// named that channel.
+    pub fn analog_channel_name(&self, index: usize) -> Option<&str> {
+        self.analog_names.get(index).map(String::as_str)
+    }
+
+    // persistence
+
+    /// Serialize the current output state (digital/analog outs and debounce times) as JSON to
+    /// `path`, so a later `restore_state` call can resume where this process left off, e.g.
+    /// across a restart.
+    pub fn save_state<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let encoded = try!(serde_json::to_string(&self.state).chain_err(|| "Unable to encode state"));
+        let mut f = try!(File::create(path).chain_err(|| "Unable to create state file"));
+        f.write_all(encoded.as_bytes())
+            .chain_err(|| "Unable to write state file")
+    }
+
+    /// Load a previously `save_state`d output state from `path` and push it to the card.
+    pub fn restore_state<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let mut f = try!(File::open(path).chain_err(|| "Unable to open state file"));
+        let mut s = String::new();
+        try!(f.read_to_string(&mut s).chain_err(|| "Unable to read state file"));
+        let state: State = try!(serde_json::from_str(&s).chain_err(|| "Unable to decode state"));
+        try!(self.write(&Packet::SetAnalogDigital(state.dig, state.ana1, state.ana2)));
+        try!(self.set_debounce1(state.debounce1));
+        self.set_debounce2(state.debounce2)
+    }
+
     // private
     fn write(&mut self, p: &Packet) -> Result<()> {
-        match self.hd {
-            Some(ref mut hd) => {
-                let _ = K8055::detach_and_claim(hd);
-                let data = try!(K8055::encode(p));
-
-                try!(hd.write_interrupt(0x1, &data, Duration::from_millis(1000)));
+        match self.transport {
+            Some(ref mut t) => {
+                if !t.write_packet(p) {
+                    return Err(libusb::Error::Io.into());
+                }
                 // update the internal state on output changes
-                if let Packet::SetAnalogDigital(d, a1, a2) = *p {
-                    self.state = State {
-                        dig: d,
-                        ana1: a1,
-                        ana2: a2,
-                    };
-                    Ok(())
-                } else {
-                    Err(libusb::Error::InvalidParam.into())
+                match *p {
+                    Packet::SetAnalogDigital(d, a1, a2) => {
+                        self.state.dig = d;
+                        self.state.ana1 = a1;
+                        self.state.ana2 = a2;
+                    }
+                    Packet::SetDebounce(1, time) => self.state.debounce1 = time,
+                    Packet::SetDebounce(_, time) => self.state.debounce2 = time,
+                    Packet::ResetCounter(_) => (),
+                    _ => return Err(libusb::Error::InvalidParam.into()),
                 }
+                Ok(())
             }
             None => Err(libusb::Error::NoDevice.into()),
         }
     }
 
     fn read(&mut self) -> Result<Packet> {
-        match self.hd {
-            Some(ref mut hd) => {
-                let _ = K8055::detach_and_claim(hd);
-                let mut data = [0u8; 8];
-                try!(hd.read_interrupt(0x81, &mut data, Duration::from_millis(1000)));
-                K8055::decode(&data)
-            }
+        match self.transport {
+            Some(ref mut t) => t.read_packet().ok_or_else(|| libusb::Error::Io.into()),
             None => Err(libusb::Error::NoDevice.into()),
         }
     }
+}
 
-    fn encode(p: &Packet) -> Result<[u8; 8]> {
-        match *p {
-            Packet::SetAnalogDigital(dig, ana1, ana2) => {
-                Ok([5u8, dig, ana1, ana2, 0u8, 0u8, 0u8, 0u8])
+/// The four card addresses a `K8055Bank` scans for when enumerating the bus.
+const BANK_ADDRESSES: [CardAddress; 4] = [
+    CardAddress::CARD_1,
+    CardAddress::CARD_2,
+    CardAddress::CARD_3,
+    CardAddress::CARD_4,
+];
+
+/// Manages every Vellemann K8055 card found on the bus at once.
+///
+/// Scans `CARD_1..CARD_4` and hands back each responding card as a `K8055` addressable by its
+/// `CardAddress`. Besides addressing a single card through `card()`, also offers fan-out
+/// operations such as `write_digital_out_all` and `reset_all` that apply to every claimed card.
+pub struct K8055Bank<'a> {
+    cards: Vec<(CardAddress, K8055<'a>)>,
+}
+
+impl<'a> K8055Bank<'a> {
+    /// Scan the bus and open every K8055 card found among `CARD_1..CARD_4`.
+    ///
+    /// Cards that aren't connected are silently skipped. Returns an empty bank if no card
+    /// could be found at all.
+    pub fn new(ctx: &Context) -> Result<K8055Bank> {
+        let mut cards = Vec::new();
+        for &addr in BANK_ADDRESSES.iter() {
+            if let Ok(mut k) = K8055::new_addr(ctx, addr) {
+                if k.open() {
+                    cards.push((addr, k));
+                }
             }
-            _ => Err(libusb::Error::InvalidParam.into()),
         }
+        Ok(K8055Bank { cards })
     }
 
-    fn decode(d: &[u8]) -> Result<Packet> {
-        Ok(Packet::Status(d[0], d[1], d[2], d[3]))
+    /// Return a mutable handle to the card at `addr`, if it was claimed during the scan.
+    pub fn card(&mut self, addr: CardAddress) -> Option<&mut K8055<'a>> {
+        self.cards
+            .iter_mut()
+            .find(|&&mut (a, _)| a == addr)
+            .map(|&mut (_, ref mut k)| k)
     }
 
-    fn detach_and_claim(hd: &mut DeviceHandle) -> Result<()> {
-        try!(hd.kernel_driver_active(0));
-        try!(hd.detach_kernel_driver(0));
-        try!(hd.claim_interface(0));
-        Ok(())
+    /// Iterate over all claimed card handles, paired with their `CardAddress`.
+    pub fn iter_mut(&mut self) -> ::std::slice::IterMut<(CardAddress, K8055<'a>)> {
+        self.cards.iter_mut()
+    }
+
+    /// The number of cards claimed by this bank.
+    pub fn len(&self) -> usize {
+        self.cards.len()
     }
+
+    /// Whether this bank didn't manage to claim any card.
+    pub fn is_empty(&self) -> bool {
+        self.cards.is_empty()
+    }
+
+    /// The addresses of the cards claimed by this bank, in scan order.
+    pub fn addresses(&self) -> Vec<CardAddress> {
+        self.cards.iter().map(|&(addr, _)| addr).collect()
+    }
+
+    /// Apply `f` to every claimed card and aggregate whether each call succeeded.
+    ///
+    /// This is the shared implementation behind `write_digital_out_all` and `reset_all`;
+    /// reach for it directly to broadcast an operation this bank doesn't have a dedicated
+    /// helper for.
+    pub fn broadcast<F>(&mut self, mut f: F) -> Vec<(CardAddress, bool)>
+    where
+        F: FnMut(&mut K8055<'a>) -> Result<()>,
+    {
+        self.cards
+            .iter_mut()
+            .map(|&mut (addr, ref mut k)| (addr, f(k).is_ok()))
+            .collect()
+    }
+
+    /// Write the digital value `d` to the outports of every claimed card.
+    ///
+    /// Returns the per-card success bool, paired with the `CardAddress` it came from.
+    pub fn write_digital_out_all(&mut self, d: DigitalChannel) -> Vec<(CardAddress, bool)> {
+        self.broadcast(|k| k.write_digital_out(d))
+    }
+
+    /// Reset all analog and digital values to zero on every claimed card.
+    ///
+    /// Returns the per-card success bool, paired with the `CardAddress` it came from.
+    pub fn reset_all(&mut self) -> Vec<(CardAddress, bool)> {
+        self.broadcast(|k| k.reset())
+    }
+}
+
+#[test()]
+fn bank_addresses_card_and_broadcast() {
+    let mut bank = K8055Bank {
+        cards: vec![
+            (CardAddress::CARD_1, K8055::from_transport(SimTransport::new())),
+            (CardAddress::CARD_2, K8055::from_transport(SimTransport::new())),
+        ],
+    };
+    assert_eq!(bank.len(), 2);
+    assert!(!bank.is_empty());
+    assert_eq!(bank.addresses(), vec![CardAddress::CARD_1, CardAddress::CARD_2]);
+
+    assert!(bank.card(CardAddress::CARD_3).is_none());
+    assert!(bank.card(CardAddress::CARD_1).is_some());
+
+    let results = bank.write_digital_out_all(DigitalChannel::D1);
+    assert_eq!(
+        results,
+        vec![(CardAddress::CARD_1, true), (CardAddress::CARD_2, true)]
+    );
+    for (_, k) in bank.iter_mut() {
+        assert_eq!(k.get_digital_out(), DigitalChannel::D1);
+    }
+
+    let results = bank.reset_all();
+    assert_eq!(
+        results,
+        vec![(CardAddress::CARD_1, true), (CardAddress::CARD_2, true)]
+    );
+    assert_eq!(
+        bank.card(CardAddress::CARD_1).unwrap().get_digital_out(),
+        DigitalChannel::DZERO
+    );
 }
 
 #[test()]
 fn find_and_open() {
-    let mut ctx = libusb::Context::new().unwrap();
-    let mut k = K8055::new(&mut ctx).unwrap();
+    let mut k = K8055::from_transport(SimTransport::new());
+    assert!(k.open());
+    // a second call finds the transport already in place and is a no-op
     assert!(k.open());
-
-    let mut ctx = libusb::Context::new().unwrap();
-    assert!(K8055::new_addr(&mut ctx, CardAddress::CARD_2).is_err());
-    assert!(K8055::new_addr(&mut ctx, CardAddress::CARD_3).is_err());
-    assert!(K8055::new_addr(&mut ctx, CardAddress::CARD_4).is_err());
 }
 
 #[test()]
 fn write_and_read_digital() {
-    use std::thread::sleep;
-    use std::time::Duration;
-
-    let mut ctx = libusb::Context::new().unwrap();
-    let k = K8055::new(&mut ctx);
-    assert!(k.is_ok());
-    let mut k = k.unwrap();
+    let mut k = K8055::from_transport(SimTransport::new());
     assert!(k.open());
     assert!(k.get_digital_out() == DigitalChannel::DZERO);
     for i in 0..7 {
-        //    k.write_digital_out(D1).expect("DO");
         assert!(
             k.write_digital_out(DigitalChannel::from_bits(1u8 << i).unwrap())
                 .is_ok()
         );
         assert!(k.get_digital_out() == DigitalChannel::from_bits(1u8 << i).unwrap());
-        sleep(Duration::from_millis(100));
     }
     assert!(k.reset().is_ok());
     assert!(k.get_digital_out() == DigitalChannel::DZERO);
@@ -385,26 +1084,171 @@ fn write_and_read_digital() {
     ).is_ok());
     assert!(k.get_digital_out() == DigitalChannel::D2);
     assert!(k.reset().is_ok());
-    sleep(Duration::from_millis(1000));
 }
 
 #[test()]
-fn write_and_read_analog() {
-    use std::thread::sleep;
-    use std::time::Duration;
+fn sim_transport_reports_injected_inputs() {
+    let mut sim = SimTransport::new();
+    sim.set_digital_in(DigitalChannel::D3);
+    sim.set_analog_in1(42);
+    let mut k = K8055::from_transport(sim);
+    assert!(k.open());
+
+    assert!(k.read_digital_in().unwrap() == DigitalChannel::D3);
+    assert!(k.read_analog_in1().unwrap() == AnalogChannel::A1(42));
+
+    assert!(k.write_digital_out(DigitalChannel::D1).is_ok());
+    assert!(k.reset().is_ok());
+}
+
+#[test()]
+fn sim_transport_records_every_packet_written_to_it() {
+    let mut sim = SimTransport::new();
+    assert!(sim.write_packet(&Packet::SetAnalogDigital(DigitalChannel::D2.bits, 10, 20)));
+    assert!(sim.write_packet(&Packet::ResetCounter(1)));
+    assert_eq!(sim.sent_packets().len(), 2);
+    assert_eq!(
+        sim.sent_packets()[0],
+        Packet::SetAnalogDigital(DigitalChannel::D2.bits, 10, 20)
+    );
+}
 
-    let mut ctx = libusb::Context::new().unwrap();
-    let k = K8055::new(&mut ctx);
-    assert!(k.is_ok());
-    let mut k = k.unwrap();
+#[test()]
+fn write_and_read_analog() {
+    let mut k = K8055::from_transport(SimTransport::new());
     assert!(k.open());
     assert!(k.get_analog_out1() == AnalogChannel::A1(0u8));
     assert!(k.get_analog_out2() == AnalogChannel::A2(0u8));
     for i in 0u8..255 {
         assert!(k.write_analog_out(AnalogChannel::A1(i)).is_ok());
         assert!(k.write_analog_out(AnalogChannel::A2(255 - i)).is_ok());
-        sleep(Duration::from_millis(10));
     }
     assert!(k.reset().is_ok());
-    sleep(Duration::from_millis(1000));
+}
+
+#[test()]
+fn counters_and_debounce() {
+    let mut k = K8055::from_transport(SimTransport::new());
+    assert!(k.open());
+
+    assert_eq!(k.read_counter1().unwrap(), 0);
+    assert_eq!(k.read_counter2().unwrap(), 0);
+
+    assert!(k.set_debounce1(10).is_ok());
+    assert!(k.set_debounce2(20).is_ok());
+    assert_eq!(k.debounce1(), 10);
+    assert_eq!(k.debounce2(), 20);
+
+    assert!(k.reset_counter1().is_ok());
+    assert!(k.reset_counter2().is_ok());
+    assert_eq!(k.read_counter1().unwrap(), 0);
+    assert_eq!(k.read_counter2().unwrap(), 0);
+}
+
+#[test()]
+fn config_loads_from_file() {
+    let path = ::std::env::temp_dir().join("k8055_test_config_loads_from_file.json");
+    let json = r#"{
+        "card_address": 21760,
+        "initial_digital_out": 3,
+        "initial_analog_out1": 10,
+        "initial_analog_out2": 20,
+        "debounce1": 5,
+        "debounce2": 6,
+        "digital_names": ["pump", "valve"],
+        "analog_names": ["level"]
+    }"#;
+    File::create(&path).unwrap().write_all(json.as_bytes()).unwrap();
+
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.card_address, CardAddress::CARD_1.bits);
+    assert_eq!(config.initial_digital_out, 3);
+    assert_eq!(config.initial_analog_out1, 10);
+    assert_eq!(config.initial_analog_out2, 20);
+    assert_eq!(config.debounce1, 5);
+    assert_eq!(config.debounce2, 6);
+    assert_eq!(config.digital_names, vec!["pump", "valve"]);
+    assert_eq!(config.analog_names, vec!["level"]);
+
+    let _ = ::std::fs::remove_file(&path);
+}
+
+#[test()]
+fn save_state_then_restore_state_round_trips() {
+    let path = ::std::env::temp_dir().join("k8055_test_save_then_restore_state.json");
+
+    let mut saved = K8055::from_transport(SimTransport::new());
+    assert!(saved.open());
+    assert!(saved.write_digital_out(DigitalChannel::D2).is_ok());
+    assert!(saved.set_debounce1(7).is_ok());
+    assert!(saved.set_debounce2(8).is_ok());
+    assert!(saved.save_state(&path).is_ok());
+
+    let mut restored = K8055::from_transport(SimTransport::new());
+    assert!(restored.open());
+    assert!(restored.restore_state(&path).is_ok());
+    assert_eq!(restored.get_digital_out(), DigitalChannel::D2);
+    assert_eq!(restored.debounce1(), 7);
+    assert_eq!(restored.debounce2(), 8);
+
+    let _ = ::std::fs::remove_file(&path);
+}
+
+#[test()]
+fn poll_events_first_call_establishes_baseline_only() {
+    let mut sim = SimTransport::new();
+    sim.set_digital_in(DigitalChannel::D1);
+    let mut k = K8055::from_transport(sim);
+    assert!(k.open());
+    assert_eq!(k.poll_events().unwrap(), vec![]);
+}
+
+#[test()]
+fn poll_events_reports_digital_edges() {
+    let mut sim = SimTransport::new();
+    sim.set_digital_in(DigitalChannel::D1);
+    let mut k = K8055::from_transport(sim);
+    assert!(k.open());
+    // pretend a prior poll already saw every input off
+    k.last_digital_in = Some(DigitalChannel::DZERO);
+
+    assert_eq!(
+        k.poll_events().unwrap(),
+        vec![Event::DigitalRising(DigitalChannel::D1)]
+    );
+    // the next call sees no further change
+    assert_eq!(k.poll_events().unwrap(), vec![]);
+}
+
+#[test()]
+fn watch_digital_in_calls_back_with_old_and_new() {
+    let mut sim = SimTransport::new();
+    sim.set_digital_in(DigitalChannel::D2);
+    let mut k = K8055::from_transport(sim);
+    assert!(k.open());
+    k.last_digital_in = Some(DigitalChannel::DZERO);
+
+    let mut seen = None;
+    k.watch_digital_in(DigitalChannel::DALL, |old, new| {
+        seen = Some((old, new));
+    }).unwrap();
+    assert_eq!(seen, Some((DigitalChannel::DZERO, DigitalChannel::D2)));
+}
+
+#[test()]
+fn watch_analog1_reports_threshold_crossings() {
+    let mut sim = SimTransport::new();
+    sim.set_analog_in1(250);
+    let mut k = K8055::from_transport(sim);
+    assert!(k.open());
+    k.watch_analog1(200, 10);
+
+    let mut events = Vec::new();
+    k.watch(|e| events.push(e)).unwrap();
+    assert_eq!(events, vec![Event::Analog1Above(250)]);
+
+    // no further event once it's already above
+    events.clear();
+    k.watch(|e| events.push(e)).unwrap();
+    assert_eq!(events, vec![]);
 }